@@ -10,12 +10,21 @@ use clap::{Command, Arg};
 use colored::*;
 use std::path::PathBuf;
 use shell::Shell;
-use crate::model::Model;
+use crate::model::{builtin_roles, ClientConfig, ExtraConfig, Model, RoleConfig};
 
 #[derive(Serialize, Deserialize)]
 struct Config {
     model: Model,
-    max_tokens: i32
+    max_tokens: i32,
+
+    #[serde(default)]
+    clients: Vec<ClientConfig>,
+
+    #[serde(default)]
+    roles: Vec<RoleConfig>,
+
+    #[serde(default)]
+    extra: ExtraConfig,
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -58,6 +67,28 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .help("Disable cache and always query the LLM")
                 .action(clap::ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("stream")
+                .long("stream")
+                .help("Stream the generated command token-by-token as it arrives")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(Arg::new("client")
+            .long("client")
+            .help("Use a registered OpenAI-compatible client by name")
+            .value_name("NAME"))
+        .arg(Arg::new("model")
+            .long("model")
+            .help("Pick which of the selected client's models to use")
+            .value_name("MODEL"))
+        .arg(Arg::new("role")
+            .long("role")
+            .help("Use a named role/preset system prompt")
+            .value_name("NAME"))
+        .arg(Arg::new("repl")
+            .long("repl")
+            .help("Start an interactive, history-aware refinement session")
+            .action(clap::ArgAction::SetTrue))
         .get_matches();
 
     let config_path = get_default_config_path().expect("Failed to get default config path");
@@ -104,17 +135,116 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         println!("{}", "Current Configuration:".cyan().bold());
         println!("{}", config.model.display_config(&shell));
         println!("{}", format!("Max Tokens: {}", config.max_tokens).cyan());
+
+        if config.clients.is_empty() {
+            println!("{}", "Clients: none registered".cyan());
+        } else {
+            println!("{}", "Clients:".cyan());
+            for client in &config.clients {
+                println!("{}", format!("  {} ({}) -> {}", client.name, client.client_type, client.endpoint).cyan());
+            }
+        }
+
+        // User-defined roles take precedence over a builtin of the same name (see role
+        // resolution below), so list each name once and skip shadowed builtins.
+        let mut roles: Vec<String> = config.roles.iter().map(|r| r.name.clone()).collect();
+        roles.extend(builtin_roles().into_iter().filter(|r| !config.roles.iter().any(|cr| cr.name == r.name)).map(|r| r.name));
+        println!("{}", format!("Roles: {}", roles.join(", ")).cyan());
+
+        println!(
+            "{}",
+            format!(
+                "Extra: proxy={}, connect_timeout={}",
+                config.extra.proxy.as_ref().map_or("none".to_string(), |_| "Set (hidden)".to_string()),
+                config.extra.connect_timeout.map_or("default".to_string(), |t| t.to_string())
+            ).cyan()
+        );
+
         return Ok(());
     }
 
     let cache_path = get_cache_path()?;
     let mut cache = load_cache(&cache_path)?;
 
+    // Resolve the selected registry client, if any. The client is cloned so an
+    // explicit `--model` can be promoted to the front of its `models` list
+    // (`get_model_name` reads the first entry).
+    let registry_owned = match matches.get_one::<String>("client") {
+        Some(name) => match config.clients.iter().find(|c| &c.name == name) {
+            Some(client) => {
+                let mut client = client.clone();
+
+                // `type` selects the wire protocol; only clients that speak the
+                // OpenAI chat-completions protocol are served here (`ollama`
+                // through its OpenAI-compatible endpoint). llama.cpp has no
+                // HTTP endpoint and remains a dedicated `Model` variant.
+                if !matches!(client.client_type.as_str(), "openai" | "openai-compatible" | "ollama") {
+                    println!("{}", format!("Error: Unsupported client type '{}' for '{}'.", client.client_type, name).red());
+                    return Ok(());
+                }
+
+                if let Some(model) = matches.get_one::<String>("model") {
+                    if !client.models.iter().any(|m| m == model) {
+                        println!("{}", format!("Error: Client '{}' does not list model '{}'.", name, model).red());
+                        return Ok(());
+                    }
+                    client.models.retain(|m| m != model);
+                    client.models.insert(0, model.clone());
+                }
+
+                if client.models.is_empty() {
+                    println!("{}", format!("Error: Client '{}' has no models configured; add one to its models list.", name).red());
+                    return Ok(());
+                }
+
+                Some(client)
+            }
+            None => {
+                println!("{}", format!("Error: No client named '{}' is registered.", name).red());
+                return Ok(());
+            }
+        },
+        None => None,
+    };
+    let registry = registry_owned.as_ref();
+
+    // Resolve the selected role, if any. User-defined roles in the config take
+    // precedence over the built-ins of the same name.
+    let role = match matches.get_one::<String>("role") {
+        Some(name) => {
+            let resolved = config.roles.iter()
+                .find(|r| &r.name == name)
+                .cloned()
+                .or_else(|| builtin_roles().into_iter().find(|r| &r.name == name));
+            match resolved {
+                Some(r) => Some(r),
+                None => {
+                    println!("{}", format!("Error: No role named '{}' is defined.", name).red());
+                    return Ok(());
+                }
+            }
+        },
+        None => None,
+    };
+
+    // Interactive REPL mode keeps a running conversation instead of one-shot
+    // prompts, so handle it before the single-prompt path.
+    if matches.get_flag("repl") {
+        return run_repl(&config, registry, role.as_ref());
+    }
+
     if let Some(prompt) = matches.get_one::<String>("prompt") {
         let disable_cache = matches.get_flag("disable-cache");
+        let stream = matches.get_flag("stream");
+
+        // Namespace the cache by role so commands don't collide across presets.
+        let cache_key = match &role {
+            Some(r) => format!("{}\u{1f}{}", r.name, prompt),
+            None => prompt.clone(),
+        };
 
         if !disable_cache {
-            if let Some(cached_command) = cache.get(prompt) {
+            if let Some(cached_command) = cache.get(&cache_key) {
                 println!("{}", "This command exists in cache".yellow());
                 println!("{}", cached_command.cyan().bold());
                 println!("{}", "Do you want to execute this command? (y/n)".yellow());
@@ -131,10 +261,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
                     if user_input.trim().to_lowercase() == "y" {
                         // Invalidate cache
-                        cache.remove(prompt);
+                        cache.remove(&cache_key);
                         save_cache(&cache_path, &cache)?;
                         // Proceed to get command from LLM
-                        get_command_from_llm(&config, &mut cache, &cache_path, prompt)?;
+                        get_command_from_llm(&config, &mut cache, &cache_path, prompt, &cache_key, stream, registry, role.as_ref())?;
                     } else {
                         println!("{}", "Command execution cancelled.".yellow());
                     }
@@ -142,11 +272,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 return Ok(());
             } else {
                 // Not in cache, proceed to get command from LLM
-                get_command_from_llm(&config, &mut cache, &cache_path, prompt)?;
+                get_command_from_llm(&config, &mut cache, &cache_path, prompt, &cache_key, stream, registry, role.as_ref())?;
             }
         } else {
             // Cache is disabled, proceed to get command from LLM
-            get_command_from_llm(&config, &mut cache, &cache_path, prompt)?;
+            get_command_from_llm(&config, &mut cache, &cache_path, prompt, &cache_key, stream, registry, role.as_ref())?;
         }
     } else {
         println!("{}", "Please provide a prompt or use one of the following options:".yellow());
@@ -156,6 +286,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         println!("{}", "  --custom-endpoint       Set custom endpoint URL".cyan());
         println!("{}", "  --custom-system-prompt  Set custom system prompt".cyan());
         println!("{}", "  --custom-api-key        Set custom API key".cyan());
+        println!("{}", "  --disable-cache         Disable cache and always query the LLM".cyan());
+        println!("{}", "  --stream                Stream the generated command token-by-token".cyan());
+        println!("{}", "  --client, --model       Use a registered client and one of its models".cyan());
+        println!("{}", "  --role                  Use a named role/preset system prompt".cyan());
+        println!("{}", "  --repl                  Start an interactive, history-aware refinement session".cyan());
     }
 
     Ok(())
@@ -180,7 +315,7 @@ fn load_or_create_config(path: &PathBuf) -> Result<Config, Box<dyn std::error::E
 
 fn create_config() -> Result<Config, io::Error> {
     let model = loop {
-        println!("{}", "Select model:\n 1 for gpt-4o-mini\n 2 for gpt-4o\n 3 for ollama (llama3.1)\n 4 for custom model".cyan());
+        println!("{}", "Select model:\n 1 for gpt-4o-mini\n 2 for gpt-4o\n 3 for ollama (llama3.1)\n 4 for custom model\n 5 for local llama.cpp (GGUF)".cyan());
 
         io::stdout().flush()?;
         let mut choice = String::new();
@@ -229,6 +364,28 @@ fn create_config() -> Result<Config, io::Error> {
                     api_key,
                 };
             },
+            "5" => {
+                print!("{}", "Enter path to GGUF model file: ".cyan());
+                io::stdout().flush()?;
+                let mut model_path = String::new();
+                io::stdin().read_line(&mut model_path)?;
+                let model_path = model_path.trim().to_string();
+
+                let n_ctx = loop {
+                    print!("{}", "Enter context size (e.g. 2048): ".cyan());
+                    io::stdout().flush()?;
+                    let mut input = String::new();
+                    io::stdin().read_line(&mut input)?;
+                    if let Ok(n) = input.trim().parse::<u32>() {
+                        if n > 0 {
+                            break n;
+                        }
+                    }
+                    println!("{}", "Invalid input. Please enter a positive integer.".red());
+                };
+
+                break Model::LlamaCpp { model_path, n_ctx };
+            },
             _ => println!("{}", "Invalid choice. Please try again.".red()),
         }
     };
@@ -249,6 +406,9 @@ fn create_config() -> Result<Config, io::Error> {
     Ok(Config {
         model,
         max_tokens,
+        clients: Vec::new(),
+        roles: Vec::new(),
+        extra: ExtraConfig::default(),
     })
 }
 
@@ -272,28 +432,42 @@ fn save_cache(path: &PathBuf, cache: &HashMap<String, String>) -> Result<(), Box
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 fn get_command_from_llm(
     config: &Config,
     cache: &mut HashMap<String, String>,
     cache_path: &PathBuf,
-    prompt: &String,
+    prompt: &str,
+    cache_key: &str,
+    stream: bool,
+    registry: Option<&ClientConfig>,
+    role: Option<&RoleConfig>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    match &config.model.llm_get_command(config, prompt.as_str()) {
+    let result = if stream {
+        config.model.llm_get_command_stream(config, prompt, registry, role)
+    } else {
+        config.model.llm_get_command(config, prompt, registry, role)
+    };
+
+    match &result {
         Ok(Some(command)) => {
-            println!("{}", &command.cyan().bold());
+            // In streaming mode the command has already been printed as it arrived.
+            if !stream {
+                println!("{}", &command.cyan().bold());
+            }
             println!("{}", "Do you want to execute this command? (y/n)".yellow());
 
             let mut user_input = String::new();
             io::stdin().read_line(&mut user_input)?;
 
             if user_input.trim().to_lowercase() == "y" {
-                execute_command(&command)?;
+                execute_command(command)?;
             } else {
                 println!("{}", "Command execution cancelled.".yellow());
             }
 
             // Save command to cache
-            cache.insert(prompt.clone(), command.clone());
+            cache.insert(cache_key.to_string(), command.clone());
             save_cache(cache_path, cache)?;
         },
         Ok(None) => println!("{}", "No command could be generated.".yellow()),
@@ -306,7 +480,7 @@ fn get_command_from_llm(
 fn execute_command(command: &str) -> Result<(), Box<dyn std::error::Error>> {
     let (shell_cmd, shell_arg) = Shell::detect().to_shell_command_and_command_arg();
 
-    match ProcessCommand::new(shell_cmd).arg(shell_arg).arg(&command).output() {
+    match ProcessCommand::new(shell_cmd).arg(shell_arg).arg(command).output() {
         Ok(output) => {
             println!("{}", "Command output:".green().bold());
             io::stdout().write_all(&output.stdout)?;
@@ -316,4 +490,108 @@ fn execute_command(command: &str) -> Result<(), Box<dyn std::error::Error>> {
     }
 
     Ok(())
+}
+
+fn get_repl_history_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let exe_path = std::env::current_exe()?;
+    let exe_dir = exe_path.parent().ok_or("Failed to get executable directory")?;
+    Ok(exe_dir.join("repl_history.txt"))
+}
+
+fn get_repl_session_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let exe_path = std::env::current_exe()?;
+    let exe_dir = exe_path.parent().ok_or("Failed to get executable directory")?;
+    Ok(exe_dir.join("repl_session.json"))
+}
+
+// Runs an interactive refinement session, resuming prior context if any was persisted.
+fn run_repl(
+    config: &Config,
+    registry: Option<&ClientConfig>,
+    role: Option<&RoleConfig>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use reedline::{DefaultPrompt, FileBackedHistory, Reedline, Signal};
+
+    let history = Box::new(FileBackedHistory::with_file(1000, get_repl_history_path()?)?);
+    let mut line_editor = Reedline::create().with_history(history);
+    let prompt = DefaultPrompt::default();
+
+    // Resume a prior conversation if one was persisted, otherwise seed a fresh
+    // one with the system prompt.
+    let session_path = get_repl_session_path()?;
+    let mut messages: Vec<(String, String)> = fs::read_to_string(&session_path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .filter(|m: &Vec<(String, String)>| !m.is_empty())
+        .unwrap_or_else(|| vec![("system".to_string(), config.model.system_prompt(role))]);
+
+    // An explicit `--role` on this invocation always wins over whatever system
+    // message was persisted from a prior session.
+    if role.is_some() {
+        let system_prompt = config.model.system_prompt(role);
+        if messages[0].0 == "system" {
+            messages[0].1 = system_prompt;
+        } else {
+            messages.insert(0, ("system".to_string(), system_prompt));
+        }
+    }
+
+    // Built once so the llama.cpp backend loads its model a single time for
+    // the whole REPL run instead of on every turn.
+    let chat_session = config.model.start_session()?;
+
+    println!("{}", "Interactive mode. Type a request, or 'exit' to quit.".cyan());
+
+    while let Signal::Success(buffer) = line_editor.read_line(&prompt)? {
+        let input = buffer.trim();
+        if input.is_empty() {
+            continue;
+        }
+        if input == "exit" || input == "quit" {
+            break;
+        }
+
+        messages.push(("user".to_string(), input.to_string()));
+
+        match config.model.llm_chat(config, &messages, registry, &chat_session) {
+            Ok(Some(command)) => {
+                println!("{}", command.cyan().bold());
+                messages.push(("assistant".to_string(), command.clone()));
+
+                println!("{}", "Do you want to execute this command? (y/n)".yellow());
+                let mut answer = String::new();
+                io::stdin().read_line(&mut answer)?;
+                if answer.trim().to_lowercase() == "y" {
+                    let output = run_command_capture(&command)?;
+                    print!("{}", output);
+                    messages.push((
+                        "system".to_string(),
+                        format!("The command was executed and produced:\n{}", output),
+                    ));
+                } else {
+                    println!("{}", "Command execution cancelled.".yellow());
+                }
+            }
+            Ok(None) => println!("{}", "No command could be generated.".yellow()),
+            Err(e) => eprintln!("{}", format!("Error: {}", e).red()),
+        }
+
+        // Persist the conversation so a restart resumes with prior context.
+        if let Ok(content) = serde_json::to_string_pretty(&messages) {
+            fs::write(&session_path, content)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Runs a command through the detected shell and returns its combined stdout/stderr.
+fn run_command_capture(command: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let (shell_cmd, shell_arg) = Shell::detect().to_shell_command_and_command_arg();
+    let output = ProcessCommand::new(shell_cmd).arg(shell_arg).arg(command).output()?;
+
+    let mut combined = String::new();
+    combined.push_str(&String::from_utf8_lossy(&output.stdout));
+    combined.push_str(&String::from_utf8_lossy(&output.stderr));
+    Ok(combined)
 }
\ No newline at end of file
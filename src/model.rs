@@ -1,9 +1,77 @@
-use openai_api_rust::{Auth, Message, OpenAI, Role};
-use openai_api_rust::chat::{ChatApi, ChatBody};
+use std::time::Duration;
+use openai_api_rust::Auth;
 use serde::{Deserialize, Serialize};
 use crate::Config;
 use crate::shell::Shell;
 
+/// A registered OpenAI-compatible client, selected per invocation with `--client <name>`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ClientConfig {
+    pub name: String,
+
+    /// Wire protocol: `openai`, `openai-compatible`, or `ollama`.
+    #[serde(rename = "type")]
+    pub client_type: String,
+
+    pub api_key: Option<String>,
+    pub endpoint: String,
+    pub models: Vec<String>,
+
+    #[serde(default)]
+    pub extra: ExtraConfig,
+}
+
+/// Proxy and connection-timeout settings for a client's HTTP requests.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct ExtraConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_timeout: Option<u64>,
+}
+
+/// A reusable system-prompt preset, selected per invocation with `--role <name>`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RoleConfig {
+    pub name: String,
+    pub prompt: String,
+}
+
+/// The default system prompt, also shipped as the `command-only` role.
+pub(crate) const DEFAULT_PROMPT: &str = "You are a professional IT worker who only speaks in commands: a single, \
+actionable CLI command and nothing else. You only respond by translating the user's input into that command. Be \
+very proper, as the user will execute what you say on their computer. No string delimiters, no explanations, no \
+ideation, no yapping, no formatting, no markdown, no fenced code blocks; what you return will be executed as-is. \
+No templating, use details from the request instead if needed. Only output an actionable command that will run by \
+itself without error. Do not output comments. Only output one possible command, never alternatives. If you are not \
+confident in your translation, return an empty string. Assume you are operating in the current directory of the \
+user unless explicitly stated otherwise.";
+
+/// The roles shipped with llm-term, always available even when the config file
+/// defines none of its own.
+pub fn builtin_roles() -> Vec<RoleConfig> {
+    vec![
+        RoleConfig {
+            name: "command-only".to_string(),
+            prompt: DEFAULT_PROMPT.to_string(),
+        },
+        RoleConfig {
+            name: "explain".to_string(),
+            prompt: "You translate the user's request into a single CLI command. Output the command \
+on the first line, then one comment line beginning with '# ' that briefly explains what it does. \
+No markdown and no code fences.".to_string(),
+        },
+        RoleConfig {
+            name: "dangerous-op-guard".to_string(),
+            prompt: "You translate the user's request into a single CLI command. If the command is \
+destructive or irreversible (e.g. rm -rf, dd, mkfs, DROP TABLE), prefix it with a comment line \
+beginning with '# WARNING:' describing the risk; otherwise output only the command. No markdown \
+and no code fences.".to_string(),
+        },
+    ]
+}
+
 #[derive(Serialize, Deserialize)]
 pub enum Model {
     #[serde(rename = "gpt-4o")]
@@ -22,64 +90,359 @@ pub enum Model {
         system_prompt: Option<String>,
         api_key: Option<String>,
     },
+
+    #[serde(rename = "llama-cpp")]
+    LlamaCpp {
+        model_path: String,
+        n_ctx: u32,
+    },
+}
+
+/// Persistent state for a multi-turn REPL session, built by [`Model::start_session`].
+pub enum ChatSession {
+    Stateless,
+    #[cfg(feature = "llama-cpp")]
+    LlamaCpp(LlamaCppHandle),
+}
+
+/// An already-loaded llama.cpp backend+model, kept alive across REPL turns.
+#[cfg(feature = "llama-cpp")]
+pub struct LlamaCppHandle {
+    backend: llama_cpp_2::llama_backend::LlamaBackend,
+    model: llama_cpp_2::model::LlamaModel,
+}
+
+#[cfg(feature = "llama-cpp")]
+impl LlamaCppHandle {
+    fn load(model_path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        use llama_cpp_2::llama_backend::LlamaBackend;
+        use llama_cpp_2::model::{params::LlamaModelParams, LlamaModel};
+
+        let backend = LlamaBackend::init()?;
+        let model = LlamaModel::load_from_file(&backend, model_path, &LlamaModelParams::default())?;
+        Ok(Self { backend, model })
+    }
 }
 
 impl Model {
-    pub fn llm_get_command(&self, config: &Config, user_prompt: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
-        let model_name = self.get_model_name();
-        let auth = self.get_auth();
-        let client = OpenAI::new(auth, self.get_openai_endpoint().as_str());
+    pub fn llm_get_command(&self, config: &Config, user_prompt: &str, registry: Option<&ClientConfig>, role: Option<&RoleConfig>) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        // Local GGUF models run in-process with no HTTP server to talk to.
+        if let Model::LlamaCpp { model_path, n_ctx } = self {
+            return self.llama_cpp_generate(config, user_prompt, role, model_path, *n_ctx);
+        }
 
+        let runtime = tokio::runtime::Runtime::new()?;
+        runtime.block_on(self.chat_completion(config, user_prompt, registry, role))
+    }
+
+    /// Builds whatever persistent state a multi-turn REPL needs before its loop starts.
+    pub fn start_session(&self) -> Result<ChatSession, Box<dyn std::error::Error>> {
+        #[cfg(feature = "llama-cpp")]
+        if let Model::LlamaCpp { model_path, .. } = self {
+            return Ok(ChatSession::LlamaCpp(LlamaCppHandle::load(model_path)?));
+        }
+        Ok(ChatSession::Stateless)
+    }
+
+    /// Continues a multi-turn conversation, used by the REPL. Unlike
+    /// [`Model::llm_get_command`] the caller owns the full `messages` history.
+    pub fn llm_chat(&self, config: &Config, messages: &[(String, String)], registry: Option<&ClientConfig>, session: &ChatSession) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        if let Model::LlamaCpp { model_path, n_ctx } = self {
+            #[cfg(feature = "llama-cpp")]
+            if let ChatSession::LlamaCpp(handle) = session {
+                return self.llama_cpp_chat_with_handle(config, messages, handle, *n_ctx);
+            }
+            #[cfg(not(feature = "llama-cpp"))]
+            let _ = session;
+            return self.llama_cpp_chat(config, messages, model_path, *n_ctx);
+        }
+
+        let runtime = tokio::runtime::Runtime::new()?;
+        runtime.block_on(self.chat_completion_messages(config, messages, registry))
+    }
+
+    /// The system prompt for the current shell, used by the REPL to seed its conversation.
+    pub fn system_prompt(&self, role: Option<&RoleConfig>) -> String {
+        self.get_system_prompt(&Shell::detect(), role)
+    }
+
+    async fn chat_completion_messages(&self, config: &Config, messages: &[(String, String)], registry: Option<&ClientConfig>) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let json_messages: Vec<serde_json::Value> = messages.iter()
+            .map(|(role, content)| serde_json::json!({ "role": role, "content": content }))
+            .collect();
+
+        let body = serde_json::json!({
+            "model": self.get_model_name(registry),
+            "max_tokens": config.max_tokens,
+            "temperature": 0.5,
+            "messages": json_messages,
+        });
+
+        let url = format!("{}chat/completions", self.get_openai_endpoint(registry));
+        let client = self.build_http_client(config, registry)?;
+        let response = client
+            .post(&url)
+            .bearer_auth(self.get_auth(registry).api_key)
+            .json(&body)
+            .send()
+            .await?;
+
+        let response = Self::ensure_success(response).await?;
+        let value: serde_json::Value = response.json().await?;
+        Ok(value["choices"][0]["message"]["content"].as_str().map(|s| s.to_string()))
+    }
+
+    async fn chat_completion(&self, config: &Config, user_prompt: &str, registry: Option<&ClientConfig>, role: Option<&RoleConfig>) -> Result<Option<String>, Box<dyn std::error::Error>> {
         let shell = Shell::detect();
-        let system_prompt = self.get_system_prompt(&shell);
-
-        let body = ChatBody {
-            model: model_name,
-            max_tokens: Some(config.max_tokens),
-            temperature: Some(0.5),
-            top_p: None,
-            n: None,
-            stream: None,
-            stop: None,
-            presence_penalty: None,
-            frequency_penalty: None,
-            logit_bias: None,
-            user: None,
-            messages: vec![
-                Message { role: Role::System, content: system_prompt.to_string() },
-                Message { role: Role::User, content: user_prompt.to_string() }
+        let system_prompt = self.get_system_prompt(&shell, role);
+        let url = format!("{}chat/completions", self.get_openai_endpoint(registry));
+
+        let body = serde_json::json!({
+            "model": self.get_model_name(registry),
+            "max_tokens": config.max_tokens,
+            "temperature": 0.5,
+            "messages": [
+                { "role": "system", "content": system_prompt },
+                { "role": "user", "content": user_prompt },
             ],
-        };
+        });
 
-        match client.chat_completion_create(&body) {
-            Ok(response) => Ok(response.choices.first()
-                .map(|choice| choice.message.as_ref())
-                .flatten()
-                .map(|message| message.content.clone())
-            ),
-            Err(e) => Err(format!("Error: {:?}", e).into()),
+        let client = self.build_http_client(config, registry)?;
+        let response = client
+            .post(&url)
+            .bearer_auth(self.get_auth(registry).api_key)
+            .json(&body)
+            .send()
+            .await?;
+
+        let response = Self::ensure_success(response).await?;
+        let value: serde_json::Value = response.json().await?;
+        Ok(value["choices"][0]["message"]["content"].as_str().map(|s| s.to_string()))
+    }
+
+    /// Surfaces a non-2xx response as an error instead of silently yielding no command.
+    async fn ensure_success(response: reqwest::Response) -> Result<reqwest::Response, Box<dyn std::error::Error>> {
+        let status = response.status();
+        if status.is_success() {
+            Ok(response)
+        } else {
+            let body = response.text().await.unwrap_or_default();
+            Err(format!("Error: HTTP {}: {}", status, body).into())
+        }
+    }
+
+    /// Builds the `reqwest` client for a request. A registry client's `extra`
+    /// settings override the model-level `config.extra`.
+    fn build_http_client(&self, config: &Config, registry: Option<&ClientConfig>) -> Result<reqwest::Client, Box<dyn std::error::Error>> {
+        let client_extra = registry.map(|c| &c.extra);
+
+        let mut builder = reqwest::Client::builder();
+
+        let proxy = client_extra.and_then(|e| e.proxy.clone())
+            .or_else(|| config.extra.proxy.clone())
+            .or_else(|| std::env::var("HTTPS_PROXY").ok())
+            .or_else(|| std::env::var("ALL_PROXY").ok());
+        if let Some(proxy) = proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+        }
+
+        let connect_timeout = client_extra.and_then(|e| e.connect_timeout)
+            .or(config.extra.connect_timeout);
+        if let Some(secs) = connect_timeout {
+            builder = builder.connect_timeout(Duration::from_secs(secs));
         }
+
+        Ok(builder.build()?)
     }
 
-    fn get_model_name(&self) -> String {
+    /// Streaming counterpart of [`Model::llm_get_command`], printing tokens to
+    /// stdout as they arrive.
+    pub fn llm_get_command_stream(&self, config: &Config, user_prompt: &str, registry: Option<&ClientConfig>, role: Option<&RoleConfig>) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        if let Model::LlamaCpp { model_path, n_ctx } = self {
+            // The local backend has no SSE endpoint, so it can't stream
+            // token-by-token; print the full command here instead, since
+            // callers of the streaming path assume it's already been printed.
+            let result = self.llama_cpp_generate(config, user_prompt, role, model_path, *n_ctx);
+            if let Ok(Some(command)) = &result {
+                println!("{}", command);
+            }
+            return result;
+        }
+
+        let runtime = tokio::runtime::Runtime::new()?;
+        runtime.block_on(self.stream_chat_completion(config, user_prompt, registry, role))
+    }
+
+    async fn stream_chat_completion(&self, config: &Config, user_prompt: &str, registry: Option<&ClientConfig>, role: Option<&RoleConfig>) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        use eventsource_stream::Eventsource;
+        use futures_util::StreamExt;
+        use std::io::Write;
+
+        let shell = Shell::detect();
+        let system_prompt = self.get_system_prompt(&shell, role);
+        let url = format!("{}chat/completions", self.get_openai_endpoint(registry));
+
+        let body = serde_json::json!({
+            "model": self.get_model_name(registry),
+            "max_tokens": config.max_tokens,
+            "temperature": 0.5,
+            "stream": true,
+            "messages": [
+                { "role": "system", "content": system_prompt },
+                { "role": "user", "content": user_prompt },
+            ],
+        });
+
+        let client = self.build_http_client(config, registry)?;
+        let response = client
+            .post(&url)
+            .bearer_auth(self.get_auth(registry).api_key)
+            .json(&body)
+            .send()
+            .await?;
+
+        let response = Self::ensure_success(response).await?;
+        let mut events = response.bytes_stream().eventsource();
+        let mut command = String::new();
+        let mut stdout = std::io::stdout();
+
+        while let Some(event) = events.next().await {
+            let event = event?;
+            if event.data == "[DONE]" {
+                break;
+            }
+            let chunk: serde_json::Value = serde_json::from_str(&event.data)?;
+            if let Some(content) = chunk["choices"][0]["delta"]["content"].as_str() {
+                print!("{}", content);
+                stdout.flush()?;
+                command.push_str(content);
+            }
+        }
+        println!();
+
+        if command.trim().is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(command))
+        }
+    }
+
+    /// Generates a command with a local GGUF model loaded via `llama-cpp-2`.
+    #[cfg(feature = "llama-cpp")]
+    fn llama_cpp_generate(&self, config: &Config, user_prompt: &str, role: Option<&RoleConfig>, model_path: &str, n_ctx: u32) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let shell = Shell::detect();
+        let system_prompt = self.get_system_prompt(&shell, role);
+        let messages = vec![
+            ("system".to_string(), system_prompt),
+            ("user".to_string(), user_prompt.to_string()),
+        ];
+        self.llama_cpp_chat(config, &messages, model_path, n_ctx)
+    }
+
+    #[cfg(feature = "llama-cpp")]
+    fn llama_cpp_chat(&self, config: &Config, messages: &[(String, String)], model_path: &str, n_ctx: u32) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let handle = LlamaCppHandle::load(model_path)?;
+        self.llama_cpp_chat_with_handle(config, messages, &handle, n_ctx)
+    }
+
+    /// Runs one turn of generation against an already-loaded [`LlamaCppHandle`].
+    #[cfg(feature = "llama-cpp")]
+    fn llama_cpp_chat_with_handle(&self, config: &Config, messages: &[(String, String)], handle: &LlamaCppHandle, n_ctx: u32) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        use std::num::NonZeroU32;
+        use llama_cpp_2::model::{AddBos, LlamaChatMessage, Special};
+        use llama_cpp_2::context::params::LlamaContextParams;
+        use llama_cpp_2::llama_batch::LlamaBatch;
+        use llama_cpp_2::token::data_array::LlamaTokenDataArray;
+
+        let model = &handle.model;
+        let ctx_params = LlamaContextParams::default().with_n_ctx(NonZeroU32::new(n_ctx));
+        let mut ctx = model.new_context(&handle.backend, ctx_params)?;
+
+        let chat_messages = messages.iter()
+            .map(|(role, content)| LlamaChatMessage::new(role.clone(), content.clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let template = model.get_chat_template()?;
+        let prompt = model.apply_chat_template(&template, &chat_messages, true)?;
+
+        let tokens = model.str_to_token(&prompt, AddBos::Always)?;
+        let mut batch = LlamaBatch::new(tokens.len().max(1), 1);
+        let last_index = tokens.len() as i32 - 1;
+        for (i, token) in tokens.into_iter().enumerate() {
+            batch.add(token, i as i32, &[0], i as i32 == last_index)?;
+        }
+        ctx.decode(&mut batch)?;
+
+        let mut n_cur = batch.n_tokens();
+        let mut command = String::new();
+        let mut n_decoded = 0;
+        while n_decoded < config.max_tokens {
+            let candidates = ctx.candidates_ith(batch.n_tokens() - 1);
+            let candidates = LlamaTokenDataArray::from_iter(candidates, false);
+            let token = ctx.sample_token_greedy(candidates);
+            if model.is_eog_token(token) {
+                break;
+            }
+            command.push_str(&model.token_to_str(token, Special::Tokenize)?);
+
+            batch.clear();
+            batch.add(token, n_cur, &[0], true)?;
+            n_cur += 1;
+            n_decoded += 1;
+            ctx.decode(&mut batch)?;
+        }
+
+        if command.trim().is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(command))
+        }
+    }
+
+    #[cfg(not(feature = "llama-cpp"))]
+    fn llama_cpp_generate(&self, _config: &Config, _user_prompt: &str, _role: Option<&RoleConfig>, _model_path: &str, _n_ctx: u32) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        Err("llm-term was built without the `llama-cpp` feature; rebuild with `--features llama-cpp` to use a local GGUF model.".into())
+    }
+
+    #[cfg(not(feature = "llama-cpp"))]
+    fn llama_cpp_chat(&self, _config: &Config, _messages: &[(String, String)], _model_path: &str, _n_ctx: u32) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        Err("llm-term was built without the `llama-cpp` feature; rebuild with `--features llama-cpp` to use a local GGUF model.".into())
+    }
+
+    fn get_model_name(&self, registry: Option<&ClientConfig>) -> String {
+        if let Some(client) = registry {
+            return client.models.first().cloned().unwrap_or_default();
+        }
         match self {
             Model::OpenAiGpt4o => "gpt-4o".to_string(),
             Model::OpenAiGpt4oMini => "gpt-4o-mini".to_string(),
             Model::Ollama(model_name) => model_name.to_string(),
             Model::Custom { model_name, .. } => model_name.to_string(),
+            Model::LlamaCpp { model_path, .. } => model_path.to_string(),
         }
     }
 
-    fn get_openai_endpoint(&self) -> String {
+    fn get_openai_endpoint(&self, registry: Option<&ClientConfig>) -> String {
+        if let Some(client) = registry {
+            return client.endpoint.clone();
+        }
         match self {
             Model::OpenAiGpt4o => "https://api.openai.com/v1/".to_string(),
             Model::OpenAiGpt4oMini => "https://api.openai.com/v1/".to_string(),
             Model::Ollama(_) => "http://localhost:11434/v1/".to_string(),
             Model::Custom { endpoint, .. } => endpoint.to_string(),
+            Model::LlamaCpp { .. } => String::new(),
         }
     }
 
-    fn get_auth(&self) -> Auth {
+    fn get_auth(&self, registry: Option<&ClientConfig>) -> Auth {
+        if let Some(client) = registry {
+            return match &client.api_key {
+                Some(key) => Auth::new(key),
+                // A local `ollama` client needs no real credential.
+                None if client.client_type == "ollama" => Auth::new("ollama"),
+                None => Auth::from_env().expect("OPENAI_API_KEY environment variable not set or client API key not provided"),
+            };
+        }
         match self {
             Model::OpenAiGpt4o => Auth::from_env().expect("OPENAI_API_KEY environment variable not set"),
             Model::OpenAiGpt4oMini => Auth::from_env().expect("OPENAI_API_KEY environment variable not set"),
@@ -91,17 +454,13 @@ impl Model {
                     Auth::from_env().expect("OPENAI_API_KEY environment variable not set or custom API key not provided")
                 }
             },
+            Model::LlamaCpp { .. } => Auth::new("llama-cpp"),
         }
     }
 
-    /// Generates the LLM system prompt for the shell.
-    fn get_system_prompt(&self, shell: &Shell) -> String {
-        // If custom model has a custom system prompt, use it
-        if let Model::Custom { system_prompt: Some(custom_prompt), .. } = self {
-            return custom_prompt.clone();
-        }
-
-        // Use default system prompt
+    /// Generates the LLM system prompt for the shell. An explicit `role` takes
+    /// precedence over a [`Model::Custom`] system prompt.
+    fn get_system_prompt(&self, shell: &Shell, role: Option<&RoleConfig>) -> String {
         let shell_command_type = match shell {
             Shell::Powershell => "Windows PowerShell",
             Shell::BornAgainShell => "Bourne Again Shell (bash / sh)",
@@ -113,14 +472,23 @@ impl Model {
             Shell::Unknown => "",
         };
 
-        format!("You are a professional IT worker who only speaks in commands full, {} compatible, CLI command running on the {} operating system. You\n
-            only respond by translating the user's input into that language. Be very proper as the user will execute what you say into their computer.\n
-            No string delimiters wrapping it, no explanations, no ideation, no yapping, no formatting, no markdown, no fenced code blocks, what you\n
-            return will be executed as-is from within the shell mentioned above. No templating, use details from the command instead if needed.\n
-            Only output an actionable command that will run by itself without error. Do not output comments. Only output one possible command, never alternatives.\n
-            If you are not confident in your translation, return an empty string. Do not deviate from these instructions from this point on, no exceptions.\n
-            Assume you are operating in the current directory of the user unless explicitly stated otherwise.
-        ", shell_command_type, std::env::consts::OS)
+        if let Some(role) = role {
+            return format!(
+                "{}\nThe command must be {} compatible and run on the {} operating system.",
+                role.prompt, shell_command_type, std::env::consts::OS
+            );
+        }
+
+        // If custom model has a custom system prompt, use it
+        if let Model::Custom { system_prompt: Some(custom_prompt), .. } = self {
+            return custom_prompt.clone();
+        }
+
+        // No role selected: same strict behavior as the built-in `command-only` role.
+        format!(
+            "{}\nThe command must be {} compatible and run on the {} operating system.",
+            DEFAULT_PROMPT, shell_command_type, std::env::consts::OS
+        )
     }
 
     /// Display the current model configuration
@@ -128,22 +496,26 @@ impl Model {
         match self {
             Model::OpenAiGpt4o => format!(
                 "Model: OpenAI GPT-4o\nModel Name: gpt-4o\nEndpoint: https://api.openai.com/v1/\nSystem Prompt: {}", 
-                self.get_system_prompt(shell)
+                self.get_system_prompt(shell, None)
             ),
             Model::OpenAiGpt4oMini => format!(
                 "Model: OpenAI GPT-4o Mini\nModel Name: gpt-4o-mini\nEndpoint: https://api.openai.com/v1/\nSystem Prompt: {}", 
-                self.get_system_prompt(shell)
+                self.get_system_prompt(shell, None)
             ),
             Model::Ollama(model_name) => format!(
                 "Model: Ollama\nModel Name: {}\nEndpoint: http://localhost:11434/v1/\nSystem Prompt: {}", 
-                model_name, self.get_system_prompt(shell)
+                model_name, self.get_system_prompt(shell, None)
             ),
             Model::Custom { model_name, endpoint, system_prompt, api_key } => format!(
                 "Model: Custom\nModel Name: {}\nEndpoint: {}\nAPI Key: {}\nSystem Prompt: {}", 
                 model_name, 
                 endpoint,
                 api_key.as_ref().map_or("Not set".to_string(), |_| "Set (hidden)".to_string()),
-                system_prompt.as_ref().unwrap_or(&self.get_system_prompt(shell))
+                system_prompt.as_ref().unwrap_or(&self.get_system_prompt(shell, None))
+            ),
+            Model::LlamaCpp { model_path, n_ctx } => format!(
+                "Model: llama.cpp (local GGUF)\nModel Path: {}\nContext Size: {}\nSystem Prompt: {}",
+                model_path, n_ctx, self.get_system_prompt(shell, None)
             ),
         }
     }
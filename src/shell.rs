@@ -0,0 +1,61 @@
+/// The shell the generated command will be executed in.
+///
+/// Detected from the environment so the system prompt can ask the model for a
+/// compatible command and so [`Shell::to_shell_command_and_command_arg`] can
+/// spawn the right interpreter.
+#[allow(clippy::enum_variant_names)]
+pub enum Shell {
+    Powershell,
+    BornAgainShell,
+    Zsh,
+    Fish,
+    DebianAlmquistShell,
+    KornShell,
+    CShell,
+    Unknown,
+}
+
+impl Shell {
+    /// Detects the current shell from the environment.
+    ///
+    /// On Windows we assume PowerShell; elsewhere the `SHELL` environment
+    /// variable's basename selects the variant, falling back to
+    /// [`Shell::Unknown`] when it is unset or unrecognised.
+    pub fn detect() -> Shell {
+        if cfg!(target_os = "windows") {
+            return Shell::Powershell;
+        }
+
+        match std::env::var("SHELL") {
+            Ok(path) => {
+                let name = path.rsplit('/').next().unwrap_or("");
+                match name {
+                    "bash" | "sh" => Shell::BornAgainShell,
+                    "zsh" => Shell::Zsh,
+                    "fish" => Shell::Fish,
+                    "dash" => Shell::DebianAlmquistShell,
+                    "ksh" => Shell::KornShell,
+                    "csh" | "tcsh" => Shell::CShell,
+                    "pwsh" | "powershell" => Shell::Powershell,
+                    _ => Shell::Unknown,
+                }
+            }
+            Err(_) => Shell::Unknown,
+        }
+    }
+
+    /// Returns the interpreter binary and the flag used to pass a command
+    /// string to it (e.g. `("bash", "-c")`).
+    pub fn to_shell_command_and_command_arg(&self) -> (&'static str, &'static str) {
+        match self {
+            Shell::Powershell => ("powershell", "-Command"),
+            Shell::BornAgainShell => ("bash", "-c"),
+            Shell::Zsh => ("zsh", "-c"),
+            Shell::Fish => ("fish", "-c"),
+            Shell::DebianAlmquistShell => ("dash", "-c"),
+            Shell::KornShell => ("ksh", "-c"),
+            Shell::CShell => ("csh", "-c"),
+            Shell::Unknown => ("sh", "-c"),
+        }
+    }
+}